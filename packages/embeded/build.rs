@@ -5,9 +5,23 @@ use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
 use walkdir::WalkDir;
 
+/// Env var name -> platform tag used both to pick the right `include_bytes!`
+/// at build time and to match `platform_tag::detect()` at runtime.
+const PAYLOAD_ENV_VARS: &[(&str, &str)] = &[
+    ("PYEMBED_PAYLOAD_X86_64_GLIBC", "x86_64-glibc"),
+    ("PYEMBED_PAYLOAD_X86_64_MUSL", "x86_64-musl"),
+    ("PYEMBED_PAYLOAD_AARCH64_GLIBC", "aarch64-glibc"),
+    ("PYEMBED_PAYLOAD_AARCH64_MUSL", "aarch64-musl"),
+    // Untagged fallback, kept for single-target builds and backwards
+    // compatibility with the old single-payload setup.
+    ("PYEMBED_PAYLOAD", "linux"),
+];
+
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=assets/python");
-    println!("cargo:rerun-if-env-changed=PYEMBED_PAYLOAD");
+    for (env_var, _) in PAYLOAD_ENV_VARS {
+        println!("cargo:rerun-if-env-changed={env_var}");
+    }
 
     let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR")?);
     let assets_python = manifest_dir.join("assets").join("python");
@@ -42,26 +56,26 @@ fn main() -> Result<()> {
 
 fn write_embedded_payload(manifest_dir: &Path) -> Result<()> {
     let out_file = manifest_dir.join("src").join("embedded_payload.rs");
-    if let Ok(payload_path) = env::var("PYEMBED_PAYLOAD") {
+    let target = env::var("TARGET").unwrap_or_default();
+
+    let mut entries = String::new();
+    for (env_var, tag) in PAYLOAD_ENV_VARS {
+        let Ok(payload_path) = env::var(env_var) else {
+            continue;
+        };
         let payload_path = PathBuf::from(payload_path);
         println!("cargo:rerun-if-changed={}", payload_path.display());
-        let payload_name = payload_path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("python-payload.tar.zst");
-
-        let contents = format!(
-            "pub const EMBEDDED_PAYLOAD: &[u8] = include_bytes!(r#\"{}\"#);\n\
-pub const EMBEDDED_PAYLOAD_NAME: &str = \"{}\";\n",
-            payload_path.display(),
-            payload_name
-        );
-        fs::write(out_file, contents)?;
-    } else {
-        let contents = "pub const EMBEDDED_PAYLOAD: &[u8] = &[];\n\
-pub const EMBEDDED_PAYLOAD_NAME: &str = \"\";\n";
-        fs::write(out_file, contents)?;
+        entries.push_str(&format!(
+            "    ({tag:?}, include_bytes!(r#\"{}\"#) as &[u8]),\n",
+            payload_path.display()
+        ));
     }
+
+    let contents = format!(
+        "pub const TARGET: &str = {target:?};\n\
+pub const EMBEDDED_PAYLOADS: &[(&str, &[u8])] = &[\n{entries}];\n"
+    );
+    fs::write(out_file, contents)?;
     Ok(())
 }
 
@@ -0,0 +1,132 @@
+//! Routes CPython's raw memory domain through Rust's global allocator.
+//!
+//! Enabled with the `rust_allocator` Cargo feature. Combine with the
+//! `jemalloc`/`mimalloc` features to back the Rust global allocator itself
+//! with a tuned allocator; this module only concerns itself with bridging
+//! CPython's `PyMemAllocatorEx` callbacks to whatever `#[global_allocator]`
+//! is registered.
+//!
+//! Only `PYMEM_DOMAIN_RAW` is safely overridable this early: the `mem` and
+//! `object` domains may already be wired to the raw domain's previous
+//! allocator by the time we'd want to swap them, and CPython expects the
+//! raw domain override to happen before `Py_PreInitialize`.
+
+#[cfg(feature = "rust_allocator")]
+mod imp {
+    use std::alloc::{self, Layout};
+    use std::collections::HashMap;
+    use std::os::raw::c_void;
+    use std::sync::{Mutex, MutexGuard, OnceLock};
+
+    use pyo3_ffi::{PyMemAllocatorEx, PyMem_SetAllocator, PYMEM_DOMAIN_RAW};
+
+    /// CPython's raw allocator callbacks don't carry a `Layout`, so Rust's
+    /// `dealloc`/`realloc` (which require the original layout) need one
+    /// looked up by pointer. Keyed by address rather than the raw pointer so
+    /// the table is `Send`/`Sync` without extra ceremony.
+    fn layouts() -> &'static Mutex<HashMap<usize, Layout>> {
+        static LAYOUTS: OnceLock<Mutex<HashMap<usize, Layout>>> = OnceLock::new();
+        LAYOUTS.get_or_init(|| Mutex::new(HashMap::new()))
+    }
+
+    /// Locks the layout table, recovering a poisoned lock instead of
+    /// panicking. These callbacks run across the CPython FFI boundary, where
+    /// unwinding out of a poisoned `unwrap()` mid-allocation would abort the
+    /// interpreter; the map itself is still consistent after a poisoning
+    /// panic elsewhere, so it's safe to keep using.
+    fn lock_layouts() -> MutexGuard<'static, HashMap<usize, Layout>> {
+        layouts().lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    const MIN_ALIGN: usize = std::mem::size_of::<usize>() * 2;
+
+    fn layout_for(size: usize) -> Layout {
+        // Layout::from_size_align only fails on overflow/zero-align; MIN_ALIGN
+        // is a small constant and size is whatever the caller asked for.
+        Layout::from_size_align(size.max(1), MIN_ALIGN).expect("valid allocator layout")
+    }
+
+    unsafe extern "C" fn rust_malloc(_ctx: *mut c_void, size: usize) -> *mut c_void {
+        // PyMem_RawMalloc(0) must return a distinct non-NULL pointer, as if
+        // size 1 had been requested instead; NULL reads as an OOM failure to
+        // callers. layout_for already clamps to size.max(1).
+        let layout = layout_for(size);
+        let ptr = alloc::alloc(layout);
+        if ptr.is_null() {
+            return std::ptr::null_mut();
+        }
+        lock_layouts().insert(ptr as usize, layout);
+        ptr as *mut c_void
+    }
+
+    unsafe extern "C" fn rust_calloc(_ctx: *mut c_void, nelem: usize, elsize: usize) -> *mut c_void {
+        let size = match nelem.checked_mul(elsize) {
+            Some(size) => size,
+            None => return std::ptr::null_mut(),
+        };
+        // Same zero-size contract as rust_malloc: nelem*elsize == 0 still
+        // gets a real, distinct non-NULL allocation.
+        let layout = layout_for(size);
+        let ptr = alloc::alloc_zeroed(layout);
+        if ptr.is_null() {
+            return std::ptr::null_mut();
+        }
+        lock_layouts().insert(ptr as usize, layout);
+        ptr as *mut c_void
+    }
+
+    unsafe extern "C" fn rust_realloc(ctx: *mut c_void, ptr: *mut c_void, new_size: usize) -> *mut c_void {
+        if ptr.is_null() {
+            return rust_malloc(ctx, new_size);
+        }
+        // PyMem_RawRealloc(ptr, 0) follows the same non-NULL contract as
+        // PyMem_RawMalloc(0): resize down to a minimal real allocation
+        // instead of freeing and returning NULL.
+        let old_layout = match lock_layouts().remove(&(ptr as usize)) {
+            Some(layout) => layout,
+            None => return std::ptr::null_mut(),
+        };
+        // alloc::realloc requires a nonzero new_size; layout_for's size.max(1)
+        // clamp covers the same zero case as rust_malloc/rust_calloc.
+        let new_ptr = alloc::realloc(ptr as *mut u8, old_layout, new_size.max(1));
+        if new_ptr.is_null() {
+            // realloc failed; the old allocation is untouched and still live.
+            lock_layouts().insert(ptr as usize, old_layout);
+            return std::ptr::null_mut();
+        }
+        let new_layout = layout_for(new_size);
+        lock_layouts().insert(new_ptr as usize, new_layout);
+        new_ptr as *mut c_void
+    }
+
+    unsafe extern "C" fn rust_free(_ctx: *mut c_void, ptr: *mut c_void) {
+        if ptr.is_null() {
+            return;
+        }
+        if let Some(layout) = lock_layouts().remove(&(ptr as usize)) {
+            alloc::dealloc(ptr as *mut u8, layout);
+        }
+    }
+
+    /// Installs the Rust-backed allocator for `PYMEM_DOMAIN_RAW`. Must run
+    /// before `Py_PreInitialize`/`Py_InitializeFromConfig`.
+    pub unsafe fn install() {
+        // Leaked intentionally: the allocator must outlive the interpreter,
+        // and CPython never calls a teardown hook for it.
+        let allocator = Box::leak(Box::new(PyMemAllocatorEx {
+            ctx: std::ptr::null_mut(),
+            malloc: Some(rust_malloc),
+            calloc: Some(rust_calloc),
+            realloc: Some(rust_realloc),
+            free: Some(rust_free),
+        }));
+        PyMem_SetAllocator(PYMEM_DOMAIN_RAW, allocator);
+    }
+}
+
+#[cfg(not(feature = "rust_allocator"))]
+mod imp {
+    pub unsafe fn install() {}
+}
+
+pub use imp::install;
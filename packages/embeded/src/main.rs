@@ -3,19 +3,72 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use dirs::cache_dir;
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyList};
+use pyo3::types::PyDict;
 use serde::Deserialize;
-use tar::Archive;
-use zstd::stream::read::Decoder as ZstdDecoder;
 
+mod allocator;
 mod embedded_payload;
+mod memory_import;
+mod payload;
+mod platform_tag;
+mod precompile;
+mod py_init;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 #[derive(Deserialize)]
 struct PyEmbedConfig {
     python_home: String,
+    /// In `embed_mode = "extract"`, real directories under `python_root`. In
+    /// `"memory"`, archive-relative roots to search (e.g.
+    /// `"python/lib/python3.12"`) since there is no `python_root` on disk.
     python_path: Vec<String>,
+    /// Disables user site dirs and `PYTHON*` env vars, so startup is
+    /// reproducible regardless of the host environment. Defaults to on.
+    #[serde(default = "default_true")]
+    isolated: bool,
+    #[serde(default)]
+    utf8_mode: bool,
+    /// If set, run this source string instead of importing `OCTOBOT_PY_MODULE`.
+    #[serde(default)]
+    run_command: Option<String>,
+    /// If set, run this script (resolved relative to the python root) instead
+    /// of importing `OCTOBOT_PY_MODULE`.
+    #[serde(default)]
+    run_filename: Option<String>,
+    #[serde(default)]
+    embed_mode: EmbedMode,
+    /// Run `compileall` over `python_path` during (locked) extraction, so the
+    /// first real launch doesn't pay per-module compile cost. Ignored in
+    /// `embed_mode = "memory"`, which has no on-disk extraction step.
+    #[serde(default)]
+    precompile: bool,
+    /// `compileall`'s `-O`/`-OO` level: 0, 1, or 2.
+    #[serde(default)]
+    optimize: i32,
+}
+
+#[derive(Deserialize, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum EmbedMode {
+    /// Unpack the bundled payload to disk once, then run from there. Safe
+    /// default: the interpreter's own bootstrap just needs files on disk.
+    #[default]
+    Extract,
+    /// Serve modules straight out of the decompressed archive in memory; see
+    /// `memory_import` for the tradeoffs.
+    Memory,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 fn main() -> Result<()> {
@@ -24,38 +77,51 @@ fn main() -> Result<()> {
         .parent()
         .context("resolve exe parent directory")?;
 
-    let python_root = ensure_python_root(exe_dir)?;
-
-    let cfg_path = python_root.join("pyembed.json");
-    let cfg = load_config(&cfg_path)?;
-
-    let python_home = resolve_path(&python_root, &cfg.python_home);
-    let python_path = resolve_paths(&python_root, &cfg.python_path);
+    let disk_python_root = exe_dir.join("python");
+    let plan = if disk_python_root.exists() {
+        payload::ExtractionPlan::Disk(disk_python_root)
+    } else {
+        payload::plan_from_embedded_payload(exe_dir)?
+    };
 
-    env::set_var("PYTHONHOME", &python_home);
-    env::set_var("PYTHONPATH", join_paths(&python_path)?);
-    // Python 3.12+ removed stdlib distutils; ensure setuptools provides it.
-    env::set_var("SETUPTOOLS_USE_DISTUTILS", "local");
+    let cfg = match plan {
+        payload::ExtractionPlan::Disk(python_root) => {
+            let cfg = load_config(&python_root.join("pyembed.json"))?;
+            let python_home = resolve_path(&python_root, &cfg.python_home);
+            let python_path = resolve_paths(&python_root, &cfg.python_path);
+            py_init::init_python(&python_root, &python_home, &python_path, &cfg)?;
+            cfg
+        }
+        payload::ExtractionPlan::Memory {
+            archive,
+            cfg,
+            scratch_dir,
+        } => {
+            // module_search_paths is left empty: our meta_path finder, not
+            // the filesystem, resolves everything past interpreter bootstrap.
+            py_init::init_python(&scratch_dir, &scratch_dir, &[], &cfg)?;
+            Python::with_gil(|py| {
+                memory_import::install(py, archive, cfg.python_path.clone(), scratch_dir.clone())
+            })?;
+            cfg
+        }
+    };
 
-    pyo3::prepare_freethreaded_python();
+    run_entrypoint(&cfg)
+}
 
-    Python::with_gil(|py| -> Result<()> {
-        let sys = py.import("sys")?;
-        let argv = build_argv(py)?;
-        sys.setattr("argv", argv)?;
-
-        // Keep sys.path aligned with the embedded Python layout.
-        let path_list = PyList::new(py, &python_path)?;
-        sys.setattr("path", path_list)?;
-
-        // Ensure .pth files in site-packages are processed (needed for distutils shim).
-        let site = py.import("site")?;
-        for p in &python_path {
-            if p.to_string_lossy().ends_with("site-packages") {
-                site.call_method1("addsitedir", (p,))?;
-            }
+fn run_entrypoint(cfg: &PyEmbedConfig) -> Result<()> {
+    if cfg.run_command.is_some() || cfg.run_filename.is_some() {
+        // PyConfig's run_command/run_filename only take effect through
+        // Py_RunMain(), which also finalizes the interpreter on return.
+        let code = unsafe { pyo3_ffi::Py_RunMain() };
+        if code != 0 {
+            anyhow::bail!("embedded interpreter exited with status {code}");
         }
+        return Ok(());
+    }
 
+    Python::with_gil(|py| -> Result<()> {
         let module = env::var("OCTOBOT_PY_MODULE").unwrap_or_else(|_| "octobot.cli".to_string());
         let func = env::var("OCTOBOT_PY_FUNC").unwrap_or_else(|_| "main".to_string());
 
@@ -71,60 +137,7 @@ fn main() -> Result<()> {
             entry.call0()?;
         }
         Ok(())
-    })?;
-
-    Ok(())
-}
-
-fn ensure_python_root(exe_dir: &Path) -> Result<PathBuf> {
-    let python_root = exe_dir.join("python");
-    if python_root.exists() {
-        return Ok(python_root);
-    }
-
-    if embedded_payload::EMBEDDED_PAYLOAD.is_empty() {
-        anyhow::bail!(
-            "embedded Python not found at {} (expected a 'python' folder next to the binary)",
-            python_root.display()
-        );
-    }
-
-    let base_dir = if let Ok(dir) = env::var("PYEMBED_EXTRACT_DIR") {
-        PathBuf::from(dir)
-    } else {
-        cache_dir()
-            .unwrap_or_else(|| exe_dir.to_path_buf())
-            .join("octobot-pyembed")
-    };
-
-    let target_dir = base_dir.join(embedded_payload::EMBEDDED_PAYLOAD_NAME);
-    let marker = target_dir.join(".pyembed.ok");
-
-    if !marker.exists() {
-        if target_dir.exists() {
-            fs::remove_dir_all(&target_dir).context("remove stale payload dir")?;
-        }
-        fs::create_dir_all(&target_dir).context("create payload dir")?;
-        extract_payload(&target_dir)?;
-        fs::write(&marker, b"ok").context("write marker")?;
-    }
-
-    let extracted_python = target_dir.join("python");
-    if !extracted_python.exists() {
-        anyhow::bail!(
-            "embedded payload extracted but python folder missing at {}",
-            extracted_python.display()
-        );
-    }
-
-    Ok(extracted_python)
-}
-
-fn extract_payload(target_dir: &Path) -> Result<()> {
-    let decoder = ZstdDecoder::with_buffer(embedded_payload::EMBEDDED_PAYLOAD)?;
-    let mut archive = Archive::new(decoder);
-    archive.unpack(target_dir).context("unpack embedded payload")?;
-    Ok(())
+    })
 }
 
 fn load_config(path: &Path) -> Result<PyEmbedConfig> {
@@ -148,13 +161,3 @@ fn resolve_paths(root: &Path, entries: &[String]) -> Vec<PathBuf> {
     entries.iter().map(|p| resolve_path(root, p)).collect()
 }
 
-fn join_paths(paths: &[PathBuf]) -> Result<String> {
-    env::join_paths(paths)
-        .context("join PYTHONPATH")
-        .map(|s| s.to_string_lossy().to_string())
-}
-
-fn build_argv(py: Python<'_>) -> Result<pyo3::Bound<'_, PyList>> {
-    let args: Vec<String> = env::args().collect();
-    Ok(PyList::new(py, args)?)
-}
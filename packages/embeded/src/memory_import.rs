@@ -0,0 +1,222 @@
+//! Serves Python module source straight out of the decompressed embedded
+//! archive, for `embed_mode = "memory"`.
+//!
+//! Pure-Python modules (`.py`) are read from an in-memory map built once at
+//! startup and exec'd through a small `importlib.abc.MetaPathFinder`
+//! installed on `sys.meta_path`. Native extension modules (`.so`/`.pyd`)
+//! still need a real file for the dynamic loader, so those are lazily
+//! written to `scratch_dir` the first time they're imported.
+//!
+//! Known limitation: CPython's own early bootstrap (importing `encodings`,
+//! `codecs`, `io`, etc. while `Py_InitializeFromConfig` is still running)
+//! happens before our finder is installed, so it still needs those modules
+//! to be either on disk or frozen into the interpreter build. `embed_mode =
+//! "memory"` is intended for a CPython build with the bootstrap stdlib
+//! frozen in; `"extract"` remains the safe default otherwise.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyModule};
+use tar::Archive;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+/// In-memory snapshot of the embedded archive: tar entry path (e.g.
+/// `python/lib/python3.12/os.py`) to file contents.
+pub struct ModuleArchive {
+    entries: HashMap<String, Vec<u8>>,
+}
+
+impl ModuleArchive {
+    pub fn load(payload: &[u8]) -> Result<Self> {
+        let decoder = ZstdDecoder::with_buffer(payload).context("open zstd payload")?;
+        let mut archive = Archive::new(decoder);
+        let mut entries = HashMap::new();
+        for entry in archive.entries().context("read archive entries")? {
+            let mut entry = entry.context("read archive entry")?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let path = entry
+                .path()
+                .context("read archive entry path")?
+                .to_string_lossy()
+                .replace('\\', "/");
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf).context("read archive entry contents")?;
+            entries.insert(path, buf);
+        }
+        Ok(Self { entries })
+    }
+}
+
+/// Reads just `python/pyembed.json` out of a zstd+tar payload, stopping as
+/// soon as it's found instead of decompressing and buffering every entry
+/// like [`ModuleArchive::load`] does. Lets callers decide `embed_mode`
+/// up front without paying for a full archive load on the common
+/// `embed_mode = "extract"` path, where that load would otherwise be thrown
+/// away immediately after.
+///
+/// Returns `Ok(None)` only when the entry is absent; a present but malformed
+/// `pyembed.json` is an error, not silently treated the same as "missing".
+pub fn peek_config(payload: &[u8]) -> Result<Option<crate::PyEmbedConfig>> {
+    let decoder = ZstdDecoder::with_buffer(payload).context("open zstd payload")?;
+    let mut archive = Archive::new(decoder);
+    for entry in archive.entries().context("read archive entries")? {
+        let mut entry = entry.context("read archive entry")?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .context("read archive entry path")?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if path == "python/pyembed.json" {
+            let mut buf = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf).context("read archive entry contents")?;
+            let cfg = serde_json::from_slice(&buf).context("parse python/pyembed.json")?;
+            return Ok(Some(cfg));
+        }
+    }
+    Ok(None)
+}
+
+static ARCHIVE: OnceLock<ModuleArchive> = OnceLock::new();
+static ROOTS: OnceLock<Vec<String>> = OnceLock::new();
+static SCRATCH_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+fn archive() -> &'static ModuleArchive {
+    ARCHIVE.get().expect("memory archive not installed")
+}
+
+fn roots() -> &'static [String] {
+    ROOTS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Looks up a dotted module name across every configured archive root, the
+/// same way `sys.path` entries are tried in order. Returns the archive path
+/// it was found under (for `__file__`/tracebacks), whether it's a package,
+/// and the module source.
+#[pyfunction]
+fn find_source(py: Python<'_>, fullname: String) -> PyResult<Option<(String, bool, Py<PyBytes>)>> {
+    let rel = fullname.replace('.', "/");
+    for root in roots() {
+        let package_path = format!("{root}/{rel}/__init__.py");
+        if let Some(bytes) = archive().entries.get(&package_path) {
+            return Ok(Some((package_path, true, PyBytes::new(py, bytes).unbind())));
+        }
+        let module_path = format!("{root}/{rel}.py");
+        if let Some(bytes) = archive().entries.get(&module_path) {
+            return Ok(Some((module_path, false, PyBytes::new(py, bytes).unbind())));
+        }
+    }
+    Ok(None)
+}
+
+/// Writes a native extension module's bytes to `scratch_dir` on first
+/// import (idempotent) and returns the path the dynamic loader should open.
+#[pyfunction]
+fn materialize_native(fullname: String, suffix: String) -> PyResult<Option<String>> {
+    let rel = fullname.replace('.', "/");
+    for root in roots() {
+        let archive_path = format!("{root}/{rel}{suffix}");
+        if let Some(bytes) = archive().entries.get(&archive_path) {
+            let scratch_dir = SCRATCH_DIR.get().expect("scratch dir not installed");
+            let dest = scratch_dir.join(format!("{fullname}{suffix}"));
+            if !dest.exists() {
+                std::fs::write(&dest, bytes).map_err(|err| {
+                    pyo3::exceptions::PyImportError::new_err(format!(
+                        "failed to materialize native module {fullname}: {err}"
+                    ))
+                })?;
+            }
+            return Ok(Some(dest.to_string_lossy().into_owned()));
+        }
+    }
+    Ok(None)
+}
+
+const BOOTSTRAP_SRC: &str = r#"
+import importlib.abc
+import importlib.machinery
+import importlib.util
+import sys
+
+import _octobot_memory_finder
+
+class _MemoryLoader(importlib.abc.Loader):
+    def __init__(self, archive_path, source):
+        self._archive_path = archive_path
+        self._source = source
+
+    def get_filename(self, fullname):
+        return self._archive_path
+
+    def get_data(self, path):
+        return self._source
+
+    def exec_module(self, module):
+        code = compile(self._source, self._archive_path, "exec")
+        exec(code, module.__dict__)
+
+class _MemoryFinder(importlib.abc.MetaPathFinder):
+    def find_spec(self, fullname, path, target=None):
+        found = _octobot_memory_finder.find_source(fullname)
+        if found is not None:
+            archive_path, is_package, source = found
+            spec = importlib.util.spec_from_loader(
+                fullname, _MemoryLoader(archive_path, source), is_package=is_package
+            )
+            if is_package:
+                spec.submodule_search_locations = []
+            return spec
+
+        for suffix in importlib.machinery.EXTENSION_SUFFIXES:
+            materialized = _octobot_memory_finder.materialize_native(fullname, suffix)
+            if materialized is not None:
+                loader = importlib.machinery.ExtensionFileLoader(fullname, materialized)
+                return importlib.util.spec_from_file_location(
+                    fullname, materialized, loader=loader
+                )
+        return None
+
+sys.meta_path.insert(0, _MemoryFinder())
+"#;
+
+/// Installs an already-loaded in-memory archive (see [`ModuleArchive::load`])
+/// and the `sys.meta_path` finder backed by it. `archive_roots` are tar-relative directories to search, in
+/// order (e.g. `["python/lib/python3.12", "python/lib/python3.12/site-packages"]`).
+pub fn install(
+    py: Python<'_>,
+    archive: ModuleArchive,
+    archive_roots: Vec<String>,
+    scratch_dir: PathBuf,
+) -> Result<()> {
+    ARCHIVE
+        .set(archive)
+        .map_err(|_| anyhow::anyhow!("memory archive already installed"))?;
+    ROOTS
+        .set(archive_roots)
+        .map_err(|_| anyhow::anyhow!("memory archive roots already installed"))?;
+    std::fs::create_dir_all(&scratch_dir).context("create native extension scratch dir")?;
+    SCRATCH_DIR
+        .set(scratch_dir)
+        .map_err(|_| anyhow::anyhow!("scratch dir already installed"))?;
+
+    let finder_module = PyModule::new(py, "_octobot_memory_finder")?;
+    finder_module.add_function(pyo3::wrap_pyfunction!(find_source, &finder_module)?)?;
+    finder_module.add_function(pyo3::wrap_pyfunction!(materialize_native, &finder_module)?)?;
+    py.import("sys")?
+        .getattr("modules")?
+        .set_item("_octobot_memory_finder", &finder_module)?;
+
+    let bootstrap = std::ffi::CString::new(BOOTSTRAP_SRC).expect("bootstrap source has no NUL bytes");
+    py.run(bootstrap.as_c_str(), None, None)
+        .context("install in-memory meta_path finder")?;
+    Ok(())
+}
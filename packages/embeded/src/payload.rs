@@ -0,0 +1,222 @@
+//! Resolves the on-disk `python` root used by the embedded interpreter,
+//! extracting the bundled payload on first run.
+//!
+//! `build.rs` can bake in more than one payload, keyed by `<arch>-<libc>`
+//! tag (see [`crate::embedded_payload::EMBEDDED_PAYLOADS`]); [`select_payload`]
+//! picks the one matching the running machine, falling back to the
+//! untagged `linux` payload if present.
+//!
+//! Extraction is guarded by an advisory file lock so two OctoBot processes
+//! starting at once don't race to unpack into (and `remove_dir_all`) the
+//! same `target_dir`. The `.pyembed.ok` marker stores the payload's SHA-256
+//! rather than a bare sentinel, so upgrading the bundled Python is detected
+//! and forces a clean re-extract instead of silently reusing a stale cache.
+
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use dirs::cache_dir;
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use tar::Archive;
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::embedded_payload;
+use crate::memory_import::{self, ModuleArchive};
+use crate::platform_tag;
+
+/// How the embedded Python tree was resolved for this run.
+pub enum ExtractionPlan {
+    /// `python_path`/`python_home` point at real files under this directory,
+    /// same as a pre-deployed `python` folder next to the binary.
+    Disk(PathBuf),
+    /// No extraction happened; `archive` is served from memory by
+    /// [`crate::memory_import`], with `scratch_dir` reserved for native
+    /// extension modules materialized on first import.
+    Memory {
+        archive: ModuleArchive,
+        cfg: crate::PyEmbedConfig,
+        scratch_dir: PathBuf,
+    },
+}
+
+/// Picks the embedded payload matching the running machine: an exact
+/// `<arch>-<libc>` tag match first, then the untagged `linux` fallback.
+fn select_payload() -> Result<(&'static str, &'static [u8])> {
+    if embedded_payload::EMBEDDED_PAYLOADS.is_empty() {
+        anyhow::bail!(
+            "no Python is embedded in this binary (expected a 'python' folder next to it, \
+or at least one PYEMBED_PAYLOAD* to have been set at build time)"
+        );
+    }
+
+    let wanted = platform_tag::detect();
+    if let Some(&(tag, bytes)) = embedded_payload::EMBEDDED_PAYLOADS
+        .iter()
+        .find(|(tag, _)| *tag == wanted)
+    {
+        return Ok((tag, bytes));
+    }
+    if let Some(&(tag, bytes)) = embedded_payload::EMBEDDED_PAYLOADS
+        .iter()
+        .find(|(tag, _)| *tag == "linux")
+    {
+        return Ok((tag, bytes));
+    }
+    anyhow::bail!(
+        "no embedded Python payload is compatible with this machine ({wanted}); this binary was \
+built for target {} with payloads: [{}]",
+        embedded_payload::TARGET,
+        embedded_payload::EMBEDDED_PAYLOADS
+            .iter()
+            .map(|(tag, _)| *tag)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// Decides between extract and in-memory mode for an embedded payload (no
+/// pre-existing `python` folder next to the binary), based on the
+/// `embed_mode` field of the archive's own `pyembed.json`.
+pub fn plan_from_embedded_payload(exe_dir: &Path) -> Result<ExtractionPlan> {
+    let (tag, payload) = select_payload()?;
+
+    // Only peek at pyembed.json here: on the common `extract` path, a full
+    // `ModuleArchive::load` would decompress and buffer the entire payload
+    // just to be thrown away before `extract_payload` decompresses the same
+    // bytes again to unpack to disk.
+    let cfg = memory_import::peek_config(payload)
+        .context("read python/pyembed.json from embedded payload")?
+        .context("embedded archive is missing python/pyembed.json")?;
+
+    if cfg.embed_mode == crate::EmbedMode::Memory {
+        let archive = ModuleArchive::load(payload).context("decompress embedded payload")?;
+        let scratch_dir = base_dir(exe_dir).join(format!("{tag}-native"));
+        fs::create_dir_all(&scratch_dir).context("create native extension scratch dir")?;
+        Ok(ExtractionPlan::Memory {
+            archive,
+            cfg,
+            scratch_dir,
+        })
+    } else {
+        Ok(ExtractionPlan::Disk(ensure_python_root_for(
+            exe_dir, tag, payload, &cfg,
+        )?))
+    }
+}
+
+fn base_dir(exe_dir: &Path) -> PathBuf {
+    if let Ok(dir) = env::var("PYEMBED_EXTRACT_DIR") {
+        PathBuf::from(dir)
+    } else {
+        cache_dir()
+            .unwrap_or_else(|| exe_dir.to_path_buf())
+            .join("octobot-pyembed")
+    }
+}
+
+fn ensure_python_root_for(
+    exe_dir: &Path,
+    tag: &str,
+    payload: &[u8],
+    cfg: &crate::PyEmbedConfig,
+) -> Result<PathBuf> {
+    let target_dir = base_dir(exe_dir).join(tag);
+    fs::create_dir_all(&target_dir).context("create payload dir")?;
+
+    let lock_path = target_dir.join(".pyembed.lock");
+    let lock_file = File::create(&lock_path)
+        .with_context(|| format!("open extraction lock {}", lock_path.display()))?;
+    lock_file
+        .lock_exclusive()
+        .context("acquire exclusive extraction lock")?;
+
+    // Re-check after acquiring the lock: a process that waited on us may find
+    // the payload already extracted (and precompiled) and not need to redo
+    // the work.
+    let marker = target_dir.join(".pyembed.ok");
+    let expected_hash = payload_hash(payload);
+    let extracted_python = target_dir.join("python");
+    if !marker_satisfied(&marker, &expected_hash, cfg.precompile) {
+        clear_target_dir(&target_dir, &lock_path)?;
+        extract_payload(payload, &target_dir)?;
+
+        if cfg.precompile {
+            let python_home = crate::resolve_path(&extracted_python, &cfg.python_home);
+            let python_path = crate::resolve_paths(&extracted_python, &cfg.python_path);
+            crate::precompile::run(&python_home, &python_path, cfg, cfg.optimize)
+                .context("precompile extracted stdlib/app sources")?;
+        }
+
+        write_marker(&marker, &expected_hash, cfg.precompile).context("write payload marker")?;
+    }
+
+    FileExt::unlock(&lock_file).context("release extraction lock")?;
+
+    if !extracted_python.exists() {
+        anyhow::bail!(
+            "embedded payload extracted but python folder missing at {}",
+            extracted_python.display()
+        );
+    }
+
+    Ok(extracted_python)
+}
+
+fn payload_hash(payload: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(payload);
+    format!("{:x}", hasher.finalize())
+}
+
+/// A marker is satisfied when the stored hash matches the current payload
+/// and, if precompilation is enabled, a `precompiled` line is also present —
+/// so turning `precompile` on for an already-extracted payload still
+/// triggers one precompile pass instead of being silently skipped.
+fn marker_satisfied(marker: &Path, expected_hash: &str, require_precompiled: bool) -> bool {
+    let Ok(stored) = fs::read_to_string(marker) else {
+        return false;
+    };
+    let mut lines = stored.lines();
+    if lines.next() != Some(expected_hash) {
+        return false;
+    }
+    !require_precompiled || lines.next() == Some("precompiled")
+}
+
+fn write_marker(marker: &Path, hash: &str, precompiled: bool) -> Result<()> {
+    let contents = if precompiled {
+        format!("{hash}\nprecompiled\n")
+    } else {
+        format!("{hash}\n")
+    };
+    fs::write(marker, contents)
+}
+
+/// Wipes everything in `target_dir` except the lock file itself, so a stale
+/// or partial extraction from a previous payload doesn't linger.
+fn clear_target_dir(target_dir: &Path, keep: &Path) -> Result<()> {
+    for entry in fs::read_dir(target_dir).context("list payload dir")? {
+        let entry = entry?;
+        if entry.path() == keep {
+            continue;
+        }
+        if entry.file_type()?.is_dir() {
+            fs::remove_dir_all(entry.path())
+                .with_context(|| format!("remove stale dir {}", entry.path().display()))?;
+        } else {
+            fs::remove_file(entry.path())
+                .with_context(|| format!("remove stale file {}", entry.path().display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn extract_payload(payload: &[u8], target_dir: &Path) -> Result<()> {
+    let decoder = ZstdDecoder::with_buffer(payload)?;
+    let mut archive = Archive::new(decoder);
+    archive.unpack(target_dir).context("unpack embedded payload")?;
+    Ok(())
+}
@@ -0,0 +1,36 @@
+//! Detects which bundled payload matches the running machine, mirroring how
+//! `manylinux`/`musllinux` wheel tags distinguish glibc and musl builds.
+
+/// `<arch>-<libc>`, e.g. `x86_64-glibc` or `aarch64-musl`. Matched against
+/// the tags `build.rs` baked into [`crate::embedded_payload::EMBEDDED_PAYLOADS`].
+pub fn detect() -> String {
+    format!("{}-{}", std::env::consts::ARCH, detect_libc())
+}
+
+#[cfg(target_os = "linux")]
+fn detect_libc() -> &'static str {
+    // musl's dynamic loader lives at /lib/ld-musl-*; glibc's at
+    // /lib64/ld-linux-*.so.2 (or /lib/ld-linux-*.so.2 on some 32-bit/arm
+    // layouts). Only the musl loader is probed here; any machine without
+    // one is reported as glibc.
+    let has_loader_matching = |dir: &str, prefix: &str| {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|e| e.ok())
+                    .any(|e| e.file_name().to_string_lossy().starts_with(prefix))
+            })
+            .unwrap_or(false)
+    };
+
+    if has_loader_matching("/lib", "ld-musl-") {
+        "musl"
+    } else {
+        "glibc"
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn detect_libc() -> &'static str {
+    "glibc"
+}
@@ -0,0 +1,49 @@
+//! Opt-in ahead-of-time bytecode compilation of the extracted stdlib/app
+//! sources, so the first real run doesn't pay per-module compile cost.
+//!
+//! Runs inside `payload`'s locked extraction window, via a throwaway
+//! interpreter that's initialized and `Py_FinalizeEx`'d before the "real"
+//! one `py_init::init_python` sets up — CPython supports a full
+//! initialize/finalize/initialize cycle in one process, unlike juggling two
+//! interpreters concurrently.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// Runs `compileall.compile_dir(dir, optimize=optimize, quiet=1)` over every
+/// entry in `python_path`.
+pub fn run(python_home: &Path, python_path: &[PathBuf], cfg: &crate::PyEmbedConfig, optimize: i32) -> Result<()> {
+    // Safety: nothing has initialized Python in this process yet at the
+    // point `payload::ensure_python_root_for` calls this, and we finalize
+    // before returning, so the "real" py_init::init_python below still gets
+    // a clean first initialization.
+    unsafe {
+        crate::py_init::init_raw(python_home, python_home, python_path, cfg, false)?;
+    }
+
+    let result = Python::with_gil(|py| -> Result<()> {
+        let compileall = py.import("compileall").context("import compileall")?;
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("optimize", optimize)?;
+        kwargs.set_item("quiet", 1)?;
+        for dir in python_path {
+            compileall
+                .call_method("compile_dir", (dir,), Some(&kwargs))
+                .with_context(|| format!("compileall.compile_dir({})", dir.display()))?;
+        }
+        Ok(())
+    });
+
+    // Py_FinalizeEx expects to run on the thread currently holding the GIL,
+    // same as CPython's own single-threaded embedding examples.
+    let finalize_status = unsafe { pyo3_ffi::Py_FinalizeEx() };
+
+    result?;
+    if finalize_status != 0 {
+        anyhow::bail!("Py_FinalizeEx returned {finalize_status} after precompilation");
+    }
+    Ok(())
+}
@@ -0,0 +1,214 @@
+//! Drives CPython's `PyPreConfig`/`PyConfig` directly through `pyo3_ffi`.
+//!
+//! Replaces the old approach of mutating `PYTHONHOME`/`PYTHONPATH` and patching
+//! `sys.path`/`sys.argv` after `prepare_freethreaded_python()`. Configuring the
+//! interpreter up front means startup no longer depends on (and cannot leak into)
+//! the host process environment.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::Path;
+use std::sync::Once;
+
+use anyhow::{bail, Context, Result};
+use pyo3_ffi::{
+    wchar_t, PyConfig, PyConfig_Clear, PyConfig_InitIsolatedConfig, PyConfig_InitPythonConfig,
+    PyConfig_SetBytesArgv, PyConfig_SetBytesString, PyPreConfig, PyPreConfig_InitIsolatedConfig,
+    PyPreConfig_InitPythonConfig, PyStatus, PyStatus_Exception, PyWideStringList_Append,
+    Py_ExitStatusException, Py_InitializeFromConfig, Py_PreInitialize, PyEval_SaveThread,
+};
+
+use crate::PyEmbedConfig;
+
+static INIT: Once = Once::new();
+
+/// Initializes the embedded interpreter from `cfg`, bypassing env-var based setup.
+///
+/// Safe to call more than once (e.g. if a caller composes this with its own
+/// `Python::with_gil` usage); only the first call actually touches the C API.
+pub fn init_python(
+    python_root: &Path,
+    python_home: &Path,
+    python_path: &[std::path::PathBuf],
+    cfg: &PyEmbedConfig,
+) -> Result<()> {
+    // When a run_command/run_filename is configured we go straight to
+    // Py_RunMain() on this same thread without an intervening
+    // `Python::with_gil`-based module load, so the GIL must stay held here
+    // rather than being released for later reacquisition.
+    let release_gil = cfg.run_command.is_none() && cfg.run_filename.is_none();
+    let mut result = Ok(());
+    INIT.call_once(|| {
+        result = unsafe { init_raw(python_root, python_home, python_path, cfg, release_gil) };
+    });
+    result
+}
+
+/// The actual `PyPreConfig`/`PyConfig` dance, without the single-init guard.
+///
+/// Exposed so [`crate::precompile`] can spin up (and later `Py_FinalizeEx`) a
+/// throwaway interpreter before the real, `init_python`-guarded one exists.
+/// `release_gil` mirrors what `prepare_freethreaded_python()` does (so later
+/// `Python::with_gil` calls can reacquire); pass `false` for a short-lived
+/// interpreter that's used and finalized on the same thread without ever
+/// letting go of the GIL in between.
+pub unsafe fn init_raw(
+    python_root: &Path,
+    python_home: &Path,
+    python_path: &[std::path::PathBuf],
+    cfg: &PyEmbedConfig,
+    release_gil: bool,
+) -> Result<()> {
+    // The raw allocator domain can only be swapped before Py_PreInitialize.
+    crate::allocator::install();
+
+    let mut preconfig: PyPreConfig = std::mem::zeroed();
+    if cfg.isolated {
+        PyPreConfig_InitIsolatedConfig(&mut preconfig);
+    } else {
+        PyPreConfig_InitPythonConfig(&mut preconfig);
+    }
+    preconfig.utf8_mode = if cfg.utf8_mode { 1 } else { 0 };
+
+    check_status(Py_PreInitialize(&preconfig), "Py_PreInitialize")?;
+
+    let mut config: PyConfig = std::mem::zeroed();
+    if cfg.isolated {
+        PyConfig_InitIsolatedConfig(&mut config);
+    } else {
+        PyConfig_InitPythonConfig(&mut config);
+    }
+
+    let init_result = configure(&mut config as *mut PyConfig, python_root, python_home, python_path, cfg);
+    if let Err(err) = init_result {
+        PyConfig_Clear(&mut config);
+        return Err(err);
+    }
+
+    let status = Py_InitializeFromConfig(&config);
+    PyConfig_Clear(&mut config);
+    if PyStatus_Exception(status) != 0 {
+        // Py_ExitStatusException prints CPython's own diagnostic and exits the
+        // process; it never returns, which matches how the C API expects fatal
+        // init failures to be handled.
+        Py_ExitStatusException(status);
+    }
+
+    if release_gil {
+        // prepare_freethreaded_python() used to release the GIL after init so
+        // that later `Python::with_gil` calls can reacquire it; do the same here.
+        PyEval_SaveThread();
+    }
+
+    Ok(())
+}
+
+unsafe fn configure(
+    config: *mut PyConfig,
+    python_root: &Path,
+    python_home: &Path,
+    python_path: &[std::path::PathBuf],
+    cfg: &PyEmbedConfig,
+) -> Result<()> {
+    set_bytes_string(
+        config,
+        std::ptr::addr_of_mut!((*config).home),
+        &path_to_cstring(python_home)?,
+        "home",
+    )?;
+
+    let program_name = std::env::current_exe().context("resolve current_exe for program_name")?;
+    set_bytes_string(
+        config,
+        std::ptr::addr_of_mut!((*config).program_name),
+        &path_to_cstring(&program_name)?,
+        "program_name",
+    )?;
+
+    (*config).module_search_paths_set = 1;
+    for path in python_path {
+        append_search_path(config, path)?;
+    }
+
+    // Precompiled .opt-1.pyc/.opt-2.pyc files (see `precompile`) are only
+    // picked up by an interpreter running at the matching optimization
+    // level; otherwise CPython ignores them and recompiles at level 0.
+    if !(0..=2).contains(&cfg.optimize) {
+        bail!("optimize must be 0, 1, or 2, got {}", cfg.optimize);
+    }
+    (*config).optimization_level = cfg.optimize;
+
+    if let Some(run_command) = &cfg.run_command {
+        set_bytes_string(
+            config,
+            std::ptr::addr_of_mut!((*config).run_command),
+            &CString::new(run_command.as_str())?,
+            "run_command",
+        )?;
+    }
+    if let Some(run_filename) = &cfg.run_filename {
+        let abs = crate::resolve_path(python_root, run_filename);
+        set_bytes_string(
+            config,
+            std::ptr::addr_of_mut!((*config).run_filename),
+            &path_to_cstring(&abs)?,
+            "run_filename",
+        )?;
+    }
+
+    let argv: Vec<CString> = std::env::args()
+        .map(|a| CString::new(a).context("argv entry contains a NUL byte"))
+        .collect::<Result<_>>()?;
+    let mut argv_ptrs: Vec<*mut c_char> = argv.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    check_status(
+        PyConfig_SetBytesArgv(config, argv_ptrs.len() as isize, argv_ptrs.as_mut_ptr()),
+        "PyConfig_SetBytesArgv",
+    )?;
+
+    Ok(())
+}
+
+unsafe fn append_search_path(config: *mut PyConfig, path: &std::path::Path) -> Result<()> {
+    let c_path = path_to_cstring(path)?;
+    let wide = pyo3_ffi::Py_DecodeLocale(c_path.as_ptr(), std::ptr::null_mut());
+    if wide.is_null() {
+        bail!("failed to decode module search path {}", path.display());
+    }
+    let status = PyWideStringList_Append(std::ptr::addr_of_mut!((*config).module_search_paths), wide);
+    // PyWideStringList_Append copies the string; we own the temporary.
+    pyo3_ffi::PyMem_RawFree(wide as *mut std::ffi::c_void);
+    check_status(status, "PyWideStringList_Append")
+}
+
+// Takes `config` as a raw pointer (rather than `&mut PyConfig`) so call sites
+// can pass both it and `&mut (*config).some_field` without the two aliasing
+// under the borrow checker — `field` is always a sub-field of the same
+// `PyConfig` `config` points at.
+unsafe fn set_bytes_string(
+    config: *mut PyConfig,
+    field: *mut *mut wchar_t,
+    value: &CString,
+    what: &str,
+) -> Result<()> {
+    check_status(
+        PyConfig_SetBytesString(config, field, value.as_ptr()),
+        what,
+    )
+}
+
+unsafe fn check_status(status: PyStatus, what: &str) -> Result<()> {
+    if PyStatus_Exception(status) != 0 {
+        let msg = if status.err_msg.is_null() {
+            "<no message>".to_string()
+        } else {
+            std::ffi::CStr::from_ptr(status.err_msg).to_string_lossy().into_owned()
+        };
+        bail!("{what} failed: {msg}");
+    }
+    Ok(())
+}
+
+fn path_to_cstring(path: &Path) -> Result<CString> {
+    CString::new(path.to_string_lossy().as_bytes())
+        .with_context(|| format!("path {} contains a NUL byte", path.display()))
+}